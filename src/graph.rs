@@ -1,6 +1,7 @@
 //! The graph is a set of vertices and links between these vertices
 use data::Dataset;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
 /// The parameters of the model
 #[derive(Default)]
@@ -9,8 +10,10 @@ pub struct Model {
     pub spring : f64, 
     /// Importance of bubbles not-connecting
     pub repulse : f64, 
-    /// Minimum distance (between centres) of two bubbles
-    pub repulse_dist : f64, 
+    /// Minimum distance (between centres) of two bubbles, enforced as a
+    /// floor under the per-bubble `radii` + `margin` keep-apart distance
+    /// (see `Graph::cost`/`Graph::gradient`)
+    pub repulse_dist : f64,
     /// Rigidity of bubbles
     pub repulse_rigidity : f64,
     /// Importance of all bubbles forming a sphere
@@ -20,7 +23,27 @@ pub struct Model {
     /// Rigidity of containing sphere
     pub canvas_rigidity : f64,
     /// Number of blocks used for near neigbours
-    pub n_blocks : usize
+    pub n_blocks : usize,
+    /// Strategy used to decide which vertex pairs are tested for repulsion
+    pub neighborhood : Neighborhood,
+    /// Importance of avoiding edges crossing one another
+    pub crossing : f64,
+    /// Extra gap to keep between two bubbles' edges, on top of their radii
+    pub margin : f64,
+    /// Use Chebyshev (`max(|x|, |y|)`) distance for repulsion instead of
+    /// Euclidean, for tighter, grid-like packing
+    pub manhattan : bool
+}
+
+/// Strategy used to restrict repulsion to nearby vertices
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum Neighborhood {
+    /// Uniform `n_blocks` x `n_blocks` grid (see `Blocking`)
+    #[default]
+    Grid,
+    /// Neighbours from a Delaunay triangulation of the current layout;
+    /// falls back to `Grid` if the triangulation degenerates
+    Delaunay
 }
 
 /// A graph with of size `n` with a set of edges
@@ -28,7 +51,21 @@ pub struct Model {
 pub struct Graph {
     pub n: usize,
     values: HashMap<String, usize>,
-    pub edges: Vec<Edge>
+    pub edges: Vec<Edge>,
+    /// Bubble radius of each vertex, indexed like `loc` (vertex `v`'s
+    /// radius is `radii[v]`); a missing/short vector means radius `0.0`
+    pub radii: Vec<f64>,
+    /// Domain (e.g. "media", "government") of each vertex, indexed like
+    /// `loc`; a missing/short vector or an empty string means "no domain"
+    pub domains: Vec<String>,
+    /// Whether each vertex is pinned in place, indexed like `loc`; a
+    /// missing/short vector means "not pinned". `gradient` zeroes a
+    /// pinned vertex's own components so it never moves, while it still
+    /// takes part in (and is pushed against by) everyone else's forces.
+    pub pinned: Vec<bool>,
+    /// Pinned vertices' fixed `(x, y)`, indexed like `loc`; only
+    /// meaningful where `pinned[v]` is `true`
+    pub pin_loc: Vec<f64>
 }
 
 impl Graph {
@@ -37,7 +74,11 @@ impl Graph {
         Graph {
             n: 0,
             values: HashMap::new(),
-            edges : Vec::new()
+            edges : Vec::new(),
+            radii : Vec::new(),
+            domains : Vec::new(),
+            pinned : Vec::new(),
+            pin_loc : Vec::new()
         }
     }
 
@@ -63,35 +104,26 @@ impl Graph {
             cost += m.spring * d;
         }
 
-        if m.n_blocks > 1 {
-            let blocking = Blocking::create(loc, m.n_blocks);
-
-            for v1 in 0..self.n {
-                for &(v2_id, v2_x, v2_y) in blocking.nearby(loc[v1 * 2], loc[v1 * 2 + 1]).iter() {
-                    if v1 != v2_id {
-                        let x = loc[v1 * 2] - v2_x;
-                        let y = loc[v1 * 2 + 1] - v2_y;
-                        cost += repulse_cost(x, y, m);
-                    }
-                }
-            }
-        } else {
-            for v1 in 0..self.n {
-                for v2 in 0..self.n {
-                    if v1 != v2 {
-                        let x = loc[v1 * 2] - loc[v2 * 2];
-                        let y = loc[v1 * 2 + 1] - loc[v2 * 2 + 1];
-                        cost += repulse_cost(x, y, m);
-                    }
+        let neighbors = self.neighbor_lists(loc, m);
+        for v1 in 0..self.n {
+            for &v2 in neighbors[v1].iter() {
+                if v1 != v2 {
+                    let x = loc[v1 * 2] - loc[v2 * 2];
+                    let y = loc[v1 * 2 + 1] - loc[v2 * 2 + 1];
+                    let keep_apart = (self.radius(v1) + self.radius(v2) + m.margin).max(m.repulse_dist);
+                    cost += repulse_cost(x, y, keep_apart, m);
                 }
             }
         }
         for v1 in 0..self.n {
             // Centre attraction
-            let d = (loc[v1 * 2] * loc[v1 * 2] + 
+            let d = (loc[v1 * 2] * loc[v1 * 2] +
                      loc[v1 * 2 + 1] * loc[v1 * 2 + 1]).sqrt();
             cost += m.canvas * (d / m.canvas_size).powf(m.canvas_rigidity);
         }
+
+        cost += m.crossing * self.crossings(loc, m).len() as f64;
+
         cost
     }
 
@@ -113,28 +145,17 @@ impl Graph {
             }
         }
 
-        if m.n_blocks > 1 {
-            let blocking = Blocking::create(loc, m.n_blocks);
-            for v1 in 0..self.n {
-                for &(v2_id, v2_x, v2_y) in blocking.nearby(loc[v1 * 2], loc[v1 * 2 + 1]).iter() {
-                    // Repulsion 1/||vi - vj||
-                    if v1 != v2_id {
-                        let x = loc[v1 * 2] - v2_x;
-                        let y = loc[v1 * 2 + 1] - v2_y;
-                        repulse_grad(&mut gradient, x, y, v1, v2_id, m);
-                    }
-                }
-             }
-        } else {
-             for v1 in 0..self.n {
-                for v2 in 0..self.n {
-                    if v1 != v2 {
-                        let x = loc[v1 * 2] - loc[v2 * 2];
-                        let y = loc[v1 * 2 + 1] - loc[v2 * 2 + 1];
-                        repulse_grad(&mut gradient, x, y, v1, v2, m);
-                    }
+        let neighbors = self.neighbor_lists(loc, m);
+        for v1 in 0..self.n {
+            // Repulsion: softplus penalty on the pair's signed distance
+            for &v2 in neighbors[v1].iter() {
+                if v1 != v2 {
+                    let x = loc[v1 * 2] - loc[v2 * 2];
+                    let y = loc[v1 * 2 + 1] - loc[v2 * 2 + 1];
+                    let keep_apart = (self.radius(v1) + self.radius(v2) + m.margin).max(m.repulse_dist);
+                    repulse_grad(&mut gradient, x, y, keep_apart, v1, v2, m);
                 }
-             }
+            }
         }
 
         for v1 in 0..self.n {
@@ -145,30 +166,201 @@ impl Graph {
                 m.canvas_size.powf(-m.canvas_rigidity) *
                 m.canvas_rigidity * loc[v1 * 2] *
                 d.powf(m.canvas_rigidity - 2.0);
-            gradient[v1 * 2 + 1] += m.canvas * 
+            gradient[v1 * 2 + 1] += m.canvas *
                 m.canvas_size.powf(-m.canvas_rigidity) *
                 m.canvas_rigidity * loc[v1 * 2 + 1] *
                 d.powf(m.canvas_rigidity - 2.0);
         }
+
+        for (e1, e2) in self.crossings(loc, m) {
+            crossing_grad(&mut gradient, loc, &self.edges[e1], &self.edges[e2], m);
+        }
+
+        for v in 0..self.n {
+            if self.is_pinned(v) {
+                gradient[v * 2] = 0.0;
+                gradient[v * 2 + 1] = 0.0;
+            }
+        }
+
         gradient
     }
+
+    /// Pairs of edge indices (`e1 < e2`) whose segments properly cross,
+    /// found by rasterizing every edge into the cells it passes through
+    /// (a supercover line walk, so a pair sharing only a corner still gets
+    /// tested) and only running the segment-intersection test on edges
+    /// that land in a shared cell. Edges sharing a vertex are never
+    /// counted as crossing.
+    ///
+    /// `build_graph` stores every link as *two* directed edges (`src,trg`
+    /// and `trg,src`), but `crossing_grad`'s push direction depends on
+    /// which endpoint is labeled `src` - so without deduping, a real
+    /// crossing surfaces as four directed pairs whose forces are
+    /// `+, -, -, +` and cancel exactly. Only one directed edge per
+    /// undirected link is kept before grid-bucketing and testing.
+    fn crossings(&self, loc : &Vec<f64>, m : &Model) -> Vec<(usize, usize)> {
+        if m.crossing == 0.0 || self.edges.len() < 2 {
+            return Vec::new();
+        }
+
+        let mut seen_links : HashSet<(usize, usize)> = HashSet::new();
+        let mut canonical : Vec<usize> = Vec::new();
+        for (id, edge) in self.edges.iter().enumerate() {
+            let key = if edge.src < edge.trg { (edge.src, edge.trg) } else { (edge.trg, edge.src) };
+            if seen_links.insert(key) {
+                canonical.push(id);
+            }
+        }
+        if canonical.len() < 2 {
+            return Vec::new();
+        }
+
+        let canonical_edges : Vec<Edge> = canonical.iter().map(|&id| self.edges[id].clone()).collect();
+        let grid = EdgeGrid::create(loc, &canonical_edges, m.n_blocks);
+
+        let mut candidates : HashSet<(usize, usize)> = HashSet::new();
+        for ids in grid.cells.values() {
+            for i in 0..ids.len() {
+                for j in (i + 1)..ids.len() {
+                    let a = canonical[ids[i]];
+                    let b = canonical[ids[j]];
+                    candidates.insert(if a < b { (a, b) } else { (b, a) });
+                }
+            }
+        }
+
+        let mut crossings = Vec::new();
+        for (e1, e2) in candidates {
+            let edge1 = &self.edges[e1];
+            let edge2 = &self.edges[e2];
+            if edge1.src == edge2.src || edge1.src == edge2.trg ||
+               edge1.trg == edge2.src || edge1.trg == edge2.trg {
+                continue;
+            }
+            let p1 = (loc[edge1.src * 2], loc[edge1.src * 2 + 1]);
+            let p2 = (loc[edge1.trg * 2], loc[edge1.trg * 2 + 1]);
+            let p3 = (loc[edge2.src * 2], loc[edge2.src * 2 + 1]);
+            let p4 = (loc[edge2.trg * 2], loc[edge2.trg * 2 + 1]);
+            if segments_cross(p1, p2, p3, p4) {
+                crossings.push((e1, e2));
+            }
+        }
+        crossings
+    }
+
+    /// Vertex `v`'s bubble radius, or `0.0` if `radii` wasn't populated
+    /// for it (e.g. a graph not built by `build_graph`).
+    fn radius(&self, v : usize) -> f64 {
+        self.radii.get(v).cloned().unwrap_or(0.0)
+    }
+
+    /// Vertex `v`'s domain, or `""` if `domains` wasn't populated for it.
+    fn domain(&self, v : usize) -> &str {
+        self.domains.get(v).map(|d| d.as_str()).unwrap_or("")
+    }
+
+    /// Whether vertex `v` is pinned in place.
+    fn is_pinned(&self, v : usize) -> bool {
+        self.pinned.get(v).cloned().unwrap_or(false)
+    }
+
+    /// Starting locations for optimization: pinned vertices start at
+    /// their pin position (see `build_graph`'s `pins` parameter) and stay
+    /// there once `gradient` is used; unpinned vertices start at the
+    /// origin, ready for the caller to nudge with some randomness before
+    /// the first optimization step.
+    pub fn seed_loc(&self) -> Vec<f64> {
+        let mut loc = Vec::new();
+        loc.resize(self.n * 2, 0.0);
+        for v in 0..self.n {
+            if self.is_pinned(v) {
+                loc[v * 2] = self.pin_loc.get(v * 2).cloned().unwrap_or(0.0);
+                loc[v * 2 + 1] = self.pin_loc.get(v * 2 + 1).cloned().unwrap_or(0.0);
+            }
+        }
+        loc
+    }
+
+    /// Unpins every vertex, for a final light relaxation pass once the
+    /// staged layout (new datasets slotted in around last year's pinned
+    /// positions) is ready to "commit".
+    pub fn unpin_all(&mut self) {
+        for pinned in self.pinned.iter_mut() {
+            *pinned = false;
+        }
+    }
+
+    /// Vertex indices to test for repulsion against each vertex, chosen
+    /// according to `m.neighborhood`. Falls back to the `Blocking` grid (or,
+    /// if `n_blocks <= 1`, every other vertex) whenever the Delaunay
+    /// triangulation can't be built for the current layout.
+    fn neighbor_lists(&self, loc : &Vec<f64>, m : &Model) -> Vec<Vec<usize>> {
+        if m.neighborhood == Neighborhood::Delaunay {
+            if let Some(triangulation) = Triangulation::build(loc, self.n) {
+                return triangulation.adjacency;
+            }
+        }
+
+        if m.n_blocks > 1 {
+            let blocking = Blocking::create(loc, m.n_blocks);
+            (0..self.n).map(|v1| {
+                blocking.nearby(loc[v1 * 2], loc[v1 * 2 + 1]).iter()
+                    .map(|&(v2, _, _)| v2)
+                    .collect()
+            }).collect()
+        } else {
+            (0..self.n).map(|v1| {
+                (0..self.n).filter(|&v2| v2 != v1).collect()
+            }).collect()
+        }
+    }
 }
 
-fn repulse_cost(x : f64, y : f64, m : &Model) -> f64 {
-    let d = (x * x + y * y).sqrt();
-    m.repulse * relu(m.repulse_dist - d)
+/// Repulsion distance between two points: Euclidean by default, or
+/// Chebyshev (`max(|x|, |y|)`) when `m.manhattan` is set, for tighter
+/// grid-like packing.
+fn repulse_distance(x : f64, y : f64, m : &Model) -> f64 {
+    if m.manhattan {
+        x.abs().max(y.abs())
+    } else {
+        (x * x + y * y).sqrt()
+    }
 }
 
+fn repulse_cost(x : f64, y : f64, keep_apart : f64, m : &Model) -> f64 {
+    let d = repulse_distance(x, y, m);
+    let sd = d - keep_apart;
+    m.repulse * relu(-sd)
+}
 
-fn repulse_grad(gradient : &mut Vec<f64>, x : f64, y : f64,
+
+fn repulse_grad(gradient : &mut Vec<f64>, x : f64, y : f64, keep_apart : f64,
                 v1 : usize, v2 : usize, m : &Model) {
-    let d = (x * x + y * y).sqrt();
-    let s = sigma(m.repulse_dist - d);
-    if d > 0.0 {
+    let d = repulse_distance(x, y, m);
+    let sd = d - keep_apart;
+    let s = sigma(-sd);
+    if m.manhattan {
+        if d == 0.0 {
+            // Coincident pair: x.signum() is +1.0 for both +0.0 and -0.0,
+            // so the dominant-axis push below would shove both vertices
+            // the same way instead of apart. Break the tie the same way
+            // the Euclidean branch does, with an ID-related direction.
+            gradient[v1 * 2] -= m.repulse * 2.0 * s * (v1 as f64).cos() * 1e-10;
+            gradient[v1 * 2 + 1] -= m.repulse * 2.0 * s * (v2 as f64).sin() * 1e-10;
+        } else if x.abs() >= y.abs() {
+            // d = max(|x|, |y|), so its derivative is 1 along whichever
+            // axis dominates and 0 along the other - the push only acts
+            // there.
+            gradient[v1 * 2] -= m.repulse * 2.0 * s * x.signum();
+        } else {
+            gradient[v1 * 2 + 1] -= m.repulse * 2.0 * s * y.signum();
+        }
+    } else if d > 0.0 {
         gradient[v1 * 2] -= m.repulse * 2.0 * x * s / d;
         gradient[v1 * 2 + 1] -= m.repulse * 2.0 * y * s / d;
     } else {
-        // Superposition, we push in a direction related 
+        // Superposition, we push in a direction related
         // to the ID
         gradient[v1 * 2] -= m.repulse * 2.0 * s * (v1 as f64).cos() * 1e-10;
         gradient[v1 * 2 + 1] -= m.repulse * 2.0 * s * (v2 as f64).sin() * 1e-10;
@@ -270,6 +462,351 @@ impl Blocking {
     }
 }
 
+#[derive(Debug,PartialEq,Clone,Copy)]
+struct Triangle {
+    a : usize,
+    b : usize,
+    c : usize
+}
+
+/// A Delaunay triangulation, reduced to the per-vertex neighbour lists
+/// that `Graph::neighbor_lists` actually needs.
+struct Triangulation {
+    adjacency : Vec<Vec<usize>>
+}
+
+impl Triangulation {
+    /// Build a Delaunay triangulation of the `n` real vertices in `loc`
+    /// (pairs of `(x, y)`) using incremental Bowyer-Watson: insert each
+    /// point by removing every triangle whose circumcircle contains it,
+    /// then re-triangulating the resulting cavity from its boundary edges.
+    /// Returns `None` if the point set is too degenerate to triangulate
+    /// (fewer than 3 points, or all points collinear).
+    fn build(loc : &Vec<f64>, n : usize) -> Option<Triangulation> {
+        if n < 3 {
+            return None;
+        }
+
+        let mut min_x = f64::MAX;
+        let mut max_x = f64::MIN;
+        let mut min_y = f64::MAX;
+        let mut max_y = f64::MIN;
+        for i in 0..n {
+            let (x, y) = (loc[i * 2], loc[i * 2 + 1]);
+            if !x.is_finite() || !y.is_finite() {
+                return None;
+            }
+            if x < min_x { min_x = x; }
+            if x > max_x { max_x = x; }
+            if y < min_y { min_y = y; }
+            if y > max_y { max_y = y; }
+        }
+
+        let d = (max_x - min_x).max(max_y - min_y);
+        if d <= 0.0 {
+            return None;
+        }
+        let cx = (min_x + max_x) / 2.0;
+        let cy = (min_y + max_y) / 2.0;
+
+        // Real points, followed by a super-triangle large enough to
+        // enclose all of them; `s0..s2` are dropped again at the end.
+        // Each real point gets a tiny, index-keyed nudge (the same
+        // simulation-of-simplicity trick `repulse_grad` uses to separate
+        // coincident bubbles) so exact duplicates - which would otherwise
+        // make every circumcircle test below come back "outside" for the
+        // second copy - triangulate as if very close rather than identical.
+        let mut points = Vec::with_capacity(n + 3);
+        for i in 0..n {
+            let jitter = 1e-9 * (i as f64 + 1.0);
+            points.push((loc[i * 2] + jitter * (i as f64).cos(),
+                         loc[i * 2 + 1] + jitter * (i as f64).sin()));
+        }
+        let s0 = n;
+        let s1 = n + 1;
+        let s2 = n + 2;
+        points.push((cx - 20.0 * d, cy - d));
+        points.push((cx, cy + 20.0 * d));
+        points.push((cx + 20.0 * d, cy - d));
+
+        let mut triangles = vec![oriented(&points, Triangle { a: s0, b: s1, c: s2 })];
+        let mut skipped : Vec<usize> = Vec::new();
+
+        for i in 0..n {
+            let p = points[i];
+            let bad : Vec<usize> = triangles.iter().enumerate()
+                .filter(|&(_, t)| in_circumcircle(points[t.a], points[t.b], points[t.c], p))
+                .map(|(idx, _)| idx)
+                .collect();
+            if bad.is_empty() {
+                // `p` falls outside every existing circumcircle even after
+                // jittering: skip it rather than leaving a cavity with no
+                // containing triangle, and give it its nearest neighbors
+                // (see `nearest_vertices`) below instead of leaving it
+                // with none.
+                skipped.push(i);
+                continue;
+            }
+
+            // Boundary edges of the cavity are those shared by exactly
+            // one bad triangle.
+            let mut edge_count : HashMap<(usize,usize), u32> = HashMap::new();
+            for &idx in bad.iter() {
+                let t = triangles[idx];
+                for &(u, v) in [(t.a, t.b), (t.b, t.c), (t.c, t.a)].iter() {
+                    let key = if u < v { (u, v) } else { (v, u) };
+                    *edge_count.entry(key).or_insert(0) += 1;
+                }
+            }
+
+            let mut kept : Vec<Triangle> = triangles.iter().enumerate()
+                .filter(|&(idx, _)| !bad.contains(&idx))
+                .map(|(_, &t)| t)
+                .collect();
+            for (&(u, v), &count) in edge_count.iter() {
+                if count == 1 {
+                    kept.push(oriented(&points, Triangle { a: u, b: v, c: i }));
+                }
+            }
+            triangles = kept;
+        }
+
+        // Drop triangles touching the super-triangle
+        triangles.retain(|t| t.a < n && t.b < n && t.c < n);
+        if triangles.is_empty() {
+            return None;
+        }
+
+        let mut adjacency = Vec::with_capacity(n);
+        adjacency.resize(n, Vec::new());
+        let mut seen = Vec::with_capacity(n);
+        seen.resize(n, std::collections::HashSet::new());
+        for t in triangles.iter() {
+            for &(u, v) in [(t.a, t.b), (t.b, t.c), (t.c, t.a)].iter() {
+                if seen[u].insert(v) {
+                    adjacency[u].push(v);
+                }
+                if seen[v].insert(u) {
+                    adjacency[v].push(u);
+                }
+            }
+        }
+
+        // Vertices the insertion loop skipped, plus any that ended up
+        // wired only to super-triangle corners and so lost every edge in
+        // the retain above, would otherwise get no repulsion at all under
+        // Neighborhood::Delaunay; wire them to their nearest real
+        // neighbors directly instead.
+        let already_skipped : HashSet<usize> = skipped.iter().cloned().collect();
+        skipped.extend((0..n).filter(|&v| adjacency[v].is_empty() && !already_skipped.contains(&v)));
+        for &v in skipped.iter() {
+            for u in nearest_vertices(loc, n, v, 6) {
+                if seen[v].insert(u) {
+                    adjacency[v].push(u);
+                }
+                if seen[u].insert(v) {
+                    adjacency[u].push(v);
+                }
+            }
+        }
+
+        Some(Triangulation { adjacency })
+    }
+}
+
+/// The (up to) `k` vertices nearest `v` by Euclidean distance, excluding
+/// `v` itself - a brute-force fallback neighbor list for vertices the
+/// Delaunay insertion couldn't place.
+fn nearest_vertices(loc : &Vec<f64>, n : usize, v : usize, k : usize) -> Vec<usize> {
+    let mut by_dist : Vec<(f64, usize)> = (0..n).filter(|&u| u != v)
+        .map(|u| {
+            let dx = loc[u * 2] - loc[v * 2];
+            let dy = loc[u * 2 + 1] - loc[v * 2 + 1];
+            (dx * dx + dy * dy, u)
+        })
+        .collect();
+    by_dist.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    by_dist.into_iter().take(k).map(|(_, u)| u).collect()
+}
+
+/// Re-order `t`'s vertices counter-clockwise, which `in_circumcircle`
+/// assumes.
+fn oriented(points : &Vec<(f64,f64)>, t : Triangle) -> Triangle {
+    let (ax, ay) = points[t.a];
+    let (bx, by) = points[t.b];
+    let (cx, cy) = points[t.c];
+    let cross = (bx - ax) * (cy - ay) - (by - ay) * (cx - ax);
+    if cross < 0.0 {
+        Triangle { a: t.a, b: t.c, c: t.b }
+    } else {
+        t
+    }
+}
+
+/// Whether `p` lies inside the circumcircle of the counter-clockwise
+/// triangle `a`, `b`, `c`. `eps` perturbs the usual sign test so that
+/// near-cocircular points (common with grid-like layouts) don't
+/// flip-flop between "in" and "out" as floating point error accumulates.
+fn in_circumcircle(a : (f64,f64), b : (f64,f64), c : (f64,f64), p : (f64,f64)) -> bool {
+    let eps = 1e-9;
+    let (ax, ay) = (a.0 - p.0, a.1 - p.1);
+    let (bx, by) = (b.0 - p.0, b.1 - p.1);
+    let (cx, cy) = (c.0 - p.0, c.1 - p.1);
+
+    let det = (ax * ax + ay * ay) * (bx * cy - cx * by)
+            - (bx * bx + by * by) * (ax * cy - cx * ay)
+            + (cx * cx + cy * cy) * (ax * by - bx * ay);
+
+    det > eps
+}
+
+/// Buckets edge ids by the grid cell(s) their segment passes through, so
+/// that `Graph::crossings` only has to test segment-segment intersection
+/// for edges that land in a shared cell.
+struct EdgeGrid {
+    cells : HashMap<(i64,i64), Vec<usize>>
+}
+
+impl EdgeGrid {
+    fn create(loc : &Vec<f64>, edges : &Vec<Edge>, n_blocks : usize) -> EdgeGrid {
+        let n_blocks = if n_blocks > 0 { n_blocks } else { 16 };
+
+        let mut min_x = f64::MAX;
+        let mut max_x = f64::MIN;
+        let mut min_y = f64::MAX;
+        let mut max_y = f64::MIN;
+        for i in 0..(loc.len() / 2) {
+            if loc[i * 2].is_finite() && loc[i * 2 + 1].is_finite() {
+                if loc[i * 2] < min_x { min_x = loc[i * 2]; }
+                if loc[i * 2] > max_x { max_x = loc[i * 2]; }
+                if loc[i * 2 + 1] < min_y { min_y = loc[i * 2 + 1]; }
+                if loc[i * 2 + 1] > max_y { max_y = loc[i * 2 + 1]; }
+            }
+        }
+        let span = (max_x - min_x).max(max_y - min_y).max(1e-9);
+        let cell_size = span * 1.01 / (n_blocks as f64);
+
+        let mut cells : HashMap<(i64,i64), Vec<usize>> = HashMap::new();
+        for (id, edge) in edges.iter().enumerate() {
+            let x1 = loc[edge.src * 2];
+            let y1 = loc[edge.src * 2 + 1];
+            let x2 = loc[edge.trg * 2];
+            let y2 = loc[edge.trg * 2 + 1];
+            if !(x1.is_finite() && y1.is_finite() && x2.is_finite() && y2.is_finite()) {
+                continue;
+            }
+            for cell in supercover_cells(x1, y1, x2, y2, cell_size, min_x, min_y) {
+                cells.entry(cell).or_default().push(id);
+            }
+        }
+        EdgeGrid { cells }
+    }
+}
+
+/// Every grid cell the segment `(x1, y1)`-`(x2, y2)` passes through,
+/// including cells it only touches at a corner. Walks along the grid like
+/// a DDA, tracking the line-parameter distance to the next x- and
+/// y-boundary crossing; when both are crossed in the same step the two
+/// "corner" cells either side of the diagonal are emitted as well as the
+/// cell the walk lands in, which a plain Bresenham-style walk would skip.
+fn supercover_cells(x1 : f64, y1 : f64, x2 : f64, y2 : f64,
+                     cell_size : f64, min_x : f64, min_y : f64) -> Vec<(i64,i64)> {
+    let to_cell = |x : f64, y : f64| -> (i64, i64) {
+        (((x - min_x) / cell_size).floor() as i64, ((y - min_y) / cell_size).floor() as i64)
+    };
+    let (mut cx, mut cy) = to_cell(x1, y1);
+    let (ex, ey) = to_cell(x2, y2);
+
+    let dx = x2 - x1;
+    let dy = y2 - y1;
+    let step_x : i64 = if dx > 0.0 { 1 } else if dx < 0.0 { -1 } else { 0 };
+    let step_y : i64 = if dy > 0.0 { 1 } else if dy < 0.0 { -1 } else { 0 };
+
+    let frac_x = ((x1 - min_x) / cell_size).rem_euclid(1.0);
+    let frac_y = ((y1 - min_y) / cell_size).rem_euclid(1.0);
+
+    let mut t_max_x = if step_x > 0 {
+        (1.0 - frac_x) * cell_size / dx.abs()
+    } else if step_x < 0 {
+        frac_x * cell_size / dx.abs()
+    } else {
+        f64::INFINITY
+    };
+    let mut t_max_y = if step_y > 0 {
+        (1.0 - frac_y) * cell_size / dy.abs()
+    } else if step_y < 0 {
+        frac_y * cell_size / dy.abs()
+    } else {
+        f64::INFINITY
+    };
+    let t_delta_x = if step_x != 0 { cell_size / dx.abs() } else { f64::INFINITY };
+    let t_delta_y = if step_y != 0 { cell_size / dy.abs() } else { f64::INFINITY };
+
+    let mut cells = vec![(cx, cy)];
+    while (cx, cy) != (ex, ey) {
+        if t_max_x > 1.0 && t_max_y > 1.0 {
+            break;
+        }
+        if (t_max_x - t_max_y).abs() < 1e-9 {
+            // The walk crosses an x- and a y-boundary in the same step:
+            // emit both cells adjacent to the diagonal move, not just the
+            // cell the walk lands in.
+            cells.push((cx + step_x, cy));
+            cells.push((cx, cy + step_y));
+            cx += step_x;
+            cy += step_y;
+            t_max_x += t_delta_x;
+            t_max_y += t_delta_y;
+        } else if t_max_x < t_max_y {
+            cx += step_x;
+            t_max_x += t_delta_x;
+        } else {
+            cy += step_y;
+            t_max_y += t_delta_y;
+        }
+        cells.push((cx, cy));
+    }
+    cells
+}
+
+/// Whether segments `(p1, p2)` and `(p3, p4)` properly intersect, via the
+/// standard four orientation / sign-of-cross-product tests.
+fn segments_cross(p1 : (f64,f64), p2 : (f64,f64), p3 : (f64,f64), p4 : (f64,f64)) -> bool {
+    fn orient(a : (f64,f64), b : (f64,f64), c : (f64,f64)) -> f64 {
+        (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+    }
+    let d1 = orient(p3, p4, p1);
+    let d2 = orient(p3, p4, p2);
+    let d3 = orient(p1, p2, p3);
+    let d4 = orient(p1, p2, p4);
+    (d1 > 0.0) != (d2 > 0.0) && (d3 > 0.0) != (d4 > 0.0)
+}
+
+/// Moves the four endpoints of a crossing edge pair so as to shrink the
+/// quadrilateral `(e1.src, e2.src, e1.trg, e2.trg)` - the convex
+/// quadrilateral whose diagonals are the two crossing edges. The push is
+/// the shoelace-formula gradient of its signed area, signed so that
+/// descending it (every caller of `gradient` descends with
+/// `loc -= lr * grad`) moves the area toward zero whichever way round
+/// the two edges are labeled; shrinking the quadrilateral this way
+/// un-crosses the pair.
+fn crossing_grad(gradient : &mut Vec<f64>, loc : &Vec<f64>, e1 : &Edge, e2 : &Edge, m : &Model) {
+    let quad = [e1.src, e2.src, e1.trg, e2.trg];
+    let pts : Vec<(f64,f64)> = quad.iter().map(|&v| (loc[v * 2], loc[v * 2 + 1])).collect();
+    let mut area = 0.0;
+    for i in 0..4 {
+        let next = pts[(i + 1) % 4];
+        area += (pts[i].0 * next.1 - next.0 * pts[i].1) * 0.5;
+    }
+    let sign = if area > 0.0 { 1.0 } else { -1.0 };
+    for i in 0..4 {
+        let prev = pts[(i + 3) % 4];
+        let next = pts[(i + 1) % 4];
+        gradient[quad[i] * 2] += sign * m.crossing * (next.1 - prev.1) * 0.5;
+        gradient[quad[i] * 2 + 1] += sign * m.crossing * (prev.0 - next.0) * 0.5;
+    }
+}
+
 fn sigma(x : f64) -> f64 {
     1.0 / (1.0 + (-x).exp())
 }
@@ -278,14 +815,27 @@ fn relu(x : f64) -> f64 {
     (1.0 + x.exp()).ln()
 }
 
-/// Build the graph from the dataset
-pub fn build_graph(data : &HashMap<String, Dataset>) -> Graph {
+/// Build the graph from the dataset. `pins` optionally seeds (and pins in
+/// place) the named vertices at a fixed `(x, y)` - typically last run's
+/// layout, so a re-generated cloud keeps previously-placed datasets where
+/// users already found them and only lays out newly-added ones. Call
+/// `Graph::unpin_all` after optimizing to let a final pass relax everyone,
+/// including the pinned vertices, or skip it to keep them fixed forever.
+pub fn build_graph(data : &HashMap<String, Dataset>, pins : Option<&HashMap<String, (f64,f64)>>) -> Graph {
     let mut g = Graph::new();
     for dataset in data.values() {
         if !dataset.links.is_empty() {
             let v1 = g.add_vertex(&dataset.identifier);
+            set_radius(&mut g, v1, dataset);
+            set_domain(&mut g, v1, dataset);
+            set_pin(&mut g, v1, &dataset.identifier, pins);
             for link in dataset.links.iter() {
                 let v2 = g.add_vertex(&link.target);
+                if let Some(target) = data.get(&link.target) {
+                    set_radius(&mut g, v2, target);
+                    set_domain(&mut g, v2, target);
+                }
+                set_pin(&mut g, v2, &link.target, pins);
                 g.edges.push(Edge::new(v1,v2));
                 g.edges.push(Edge::new(v2,v1));
             }
@@ -293,3 +843,509 @@ pub fn build_graph(data : &HashMap<String, Dataset>) -> Graph {
     }
     g
 }
+
+/// Size bubble `v` to its dataset's triple count, so large hubs reserve
+/// the space they actually occupy instead of sharing one global radius.
+fn set_radius(g : &mut Graph, v : usize, dataset : &Dataset) {
+    if g.radii.len() <= v {
+        g.radii.resize(v + 1, 0.0);
+    }
+    g.radii[v] = (dataset.triples as f64).max(1.0).sqrt() * 0.01 + 1.0;
+}
+
+/// Record bubble `v`'s domain, for `domain_hulls` to group it with its
+/// cluster's other bubbles.
+fn set_domain(g : &mut Graph, v : usize, dataset : &Dataset) {
+    if g.domains.len() <= v {
+        g.domains.resize(v + 1, String::new());
+    }
+    g.domains[v] = dataset.domain.clone();
+}
+
+/// Pin bubble `v` at `pins[identifier]`, if given and present.
+fn set_pin(g : &mut Graph, v : usize, identifier : &str, pins : Option<&HashMap<String, (f64,f64)>>) {
+    if let Some(&(x, y)) = pins.and_then(|pins| pins.get(identifier)) {
+        if g.pinned.len() <= v {
+            g.pinned.resize(v + 1, false);
+        }
+        if g.pin_loc.len() <= v * 2 + 1 {
+            g.pin_loc.resize(v * 2 + 2, 0.0);
+        }
+        g.pinned[v] = true;
+        g.pin_loc[v * 2] = x;
+        g.pin_loc[v * 2 + 1] = y;
+    }
+}
+
+/// Parameters for `domain_hulls`' density field and contour extraction.
+pub struct HullParams {
+    /// Grid resolution used to sample the density field
+    pub width : usize,
+    pub height : usize,
+    /// How far a bubble's contribution reaches past its edge
+    pub influence : f64,
+    /// Density at which the contour boundary is drawn
+    pub threshold : f64
+}
+
+/// Escape a value for safe interpolation into a double-quoted SVG/XML
+/// attribute. `domain_hulls` builds its markup from dataset-supplied
+/// domain names and colors, which aren't trustworthy input.
+fn escape_attr(s : &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('"', "&quot;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('\'', "&#39;")
+}
+
+/// Draws a soft colored boundary around each domain's cluster of bubbles
+/// in the final layout `loc`, as one SVG `<g>` per domain containing a
+/// low-opacity filled `<path>` per disjoint blob. The sampled area spans
+/// `[-canvas_size, canvas_size]` horizontally, split into `params.width`
+/// square cells; vertically it is `params.height` cells of that same size
+/// upward from `-canvas_size`, so it reaches `canvas_size` only when
+/// `height == width`. `colors` maps domain name to an SVG fill
+/// color, falling back to a neutral gray for unlisted domains.
+pub fn domain_hulls(g : &Graph, loc : &Vec<f64>, canvas_size : f64, params : &HullParams,
+                     colors : &HashMap<String, String>) -> String {
+    let mut by_domain : HashMap<&str, Vec<usize>> = HashMap::new();
+    for v in 0..g.n {
+        let domain = g.domain(v);
+        if !domain.is_empty() {
+            by_domain.entry(domain).or_default().push(v);
+        }
+    }
+
+    let grid = Grid {
+        min_x : -canvas_size,
+        min_y : -canvas_size,
+        cell : (2.0 * canvas_size) / (params.width.max(1) as f64),
+        width : params.width,
+        height : params.height
+    };
+
+    let mut svg = String::new();
+    for (domain, vertices) in by_domain.iter() {
+        let field = density_field(g, loc, vertices, &grid, params.influence);
+        let polygons : Vec<_> = march(&field, &grid, params.threshold).into_iter()
+            .filter(|p| p.len() >= 3)
+            .collect();
+        if polygons.is_empty() {
+            continue;
+        }
+        let color = colors.get(*domain).map(|c| c.as_str()).unwrap_or("#888888");
+        svg.push_str(&format!("<g class=\"domain-hull\" data-domain=\"{}\">\n", escape_attr(domain)));
+        for polygon in polygons.iter() {
+            svg.push_str(&format!(
+                "<path d=\"{}\" fill=\"{}\" fill-opacity=\"0.15\" stroke=\"none\"/>\n",
+                polygon_to_path(polygon, &grid), escape_attr(color)));
+        }
+        svg.push_str("</g>\n");
+    }
+    svg
+}
+
+/// The sampling grid `domain_hulls` lays over the canvas: `width x
+/// height` cells covering `[min_x, min_x + width * cell] x [min_y, min_y
+/// + height * cell]`.
+struct Grid {
+    min_x : f64,
+    min_y : f64,
+    cell : f64,
+    width : usize,
+    height : usize
+}
+
+/// A `(width + 1) x (height + 1)` grid of density samples: each of
+/// `vertices` contributes `max(0, 1 - dist / influence)`, where `dist`
+/// is the distance from the grid point to the bubble's edge (its centre
+/// distance minus its radius).
+fn density_field(g : &Graph, loc : &Vec<f64>, vertices : &Vec<usize>, grid : &Grid, influence : f64) -> Vec<Vec<f64>> {
+    let mut field = Vec::with_capacity(grid.width + 1);
+    for gx in 0..=grid.width {
+        let mut column = Vec::with_capacity(grid.height + 1);
+        for gy in 0..=grid.height {
+            let px = grid.min_x + gx as f64 * grid.cell;
+            let py = grid.min_y + gy as f64 * grid.cell;
+            let mut value = 0.0;
+            for &v in vertices.iter() {
+                let dx = loc[v * 2] - px;
+                let dy = loc[v * 2 + 1] - py;
+                let dist = (dx * dx + dy * dy).sqrt() - g.radius(v);
+                value += (1.0 - dist.max(0.0) / influence).max(0.0);
+            }
+            column.push(value);
+        }
+        field.push(column);
+    }
+    field
+}
+
+/// Marching squares: extracts the iso-contour of `field` at `threshold`
+/// as closed polygons, in grid-point coordinates. Each of the `grid`'s
+/// cells is classified by a 4-bit inside/outside mask of its corners,
+/// mapped to the edges the contour crosses; the saddle cases (a mask of
+/// exactly two diagonally-opposite corners) are resolved by sampling the
+/// cell's average value against `threshold`.
+fn march(field : &Vec<Vec<f64>>, grid : &Grid, threshold : f64) -> Vec<Vec<(f64,f64)>> {
+    let mut segments = Vec::new();
+    for gx in 0..grid.width {
+        for gy in 0..grid.height {
+            let v00 = field[gx][gy];
+            let v10 = field[gx + 1][gy];
+            let v11 = field[gx + 1][gy + 1];
+            let v01 = field[gx][gy + 1];
+
+            let mask = (v00 >= threshold) as u8
+                     | ((v10 >= threshold) as u8) << 1
+                     | ((v11 >= threshold) as u8) << 2
+                     | ((v01 >= threshold) as u8) << 3;
+            if mask == 0 || mask == 15 {
+                continue;
+            }
+
+            let p00 = (gx as f64, gy as f64);
+            let p10 = (gx as f64 + 1.0, gy as f64);
+            let p11 = (gx as f64 + 1.0, gy as f64 + 1.0);
+            let p01 = (gx as f64, gy as f64 + 1.0);
+
+            let left = interp(p00, v00, p01, v01, threshold);
+            let top = interp(p00, v00, p10, v10, threshold);
+            let right = interp(p10, v10, p11, v11, threshold);
+            let bottom = interp(p01, v01, p11, v11, threshold);
+            let center_inside = (v00 + v10 + v11 + v01) / 4.0 >= threshold;
+
+            let pairs : Vec<((f64,f64),(f64,f64))> = match mask {
+                1 | 14 => vec![(left, top)],
+                2 | 13 => vec![(top, right)],
+                3 | 12 => vec![(left, right)],
+                4 | 11 => vec![(right, bottom)],
+                6 | 9 => vec![(top, bottom)],
+                7 | 8 => vec![(left, bottom)],
+                5 => if center_inside {
+                    vec![(top, right), (left, bottom)]
+                } else {
+                    vec![(left, top), (right, bottom)]
+                },
+                10 => if center_inside {
+                    vec![(left, top), (right, bottom)]
+                } else {
+                    vec![(top, right), (left, bottom)]
+                },
+                _ => Vec::new()
+            };
+            segments.extend(pairs);
+        }
+    }
+    chain_segments(segments)
+}
+
+/// Linearly interpolates the point along the edge `p1`-`p2` (with values
+/// `v1`, `v2`) where the field crosses `threshold`.
+fn interp(p1 : (f64,f64), v1 : f64, p2 : (f64,f64), v2 : f64, threshold : f64) -> (f64,f64) {
+    if (v2 - v1).abs() < 1e-12 {
+        return ((p1.0 + p2.0) / 2.0, (p1.1 + p2.1) / 2.0);
+    }
+    let t = (threshold - v1) / (v2 - v1);
+    (p1.0 + t * (p2.0 - p1.0), p1.1 + t * (p2.1 - p1.1))
+}
+
+/// Chains unordered line segments sharing endpoints into closed polygons,
+/// by repeatedly walking from an unused segment to the next unused one
+/// touching its open end until the walk returns to its starting point.
+fn chain_segments(segments : Vec<((f64,f64),(f64,f64))>) -> Vec<Vec<(f64,f64)>> {
+    let key = |p : (f64,f64)| -> (i64,i64) {
+        ((p.0 * 1e6).round() as i64, (p.1 * 1e6).round() as i64)
+    };
+    let mut endpoints : HashMap<(i64,i64), Vec<(usize,u8)>> = HashMap::new();
+    for (i, seg) in segments.iter().enumerate() {
+        endpoints.entry(key(seg.0)).or_default().push((i, 0));
+        endpoints.entry(key(seg.1)).or_default().push((i, 1));
+    }
+
+    let mut used = vec![false; segments.len()];
+    let mut polygons = Vec::new();
+    for start in 0..segments.len() {
+        if used[start] {
+            continue;
+        }
+        used[start] = true;
+        let mut polygon = vec![segments[start].0, segments[start].1];
+        loop {
+            let tail = *polygon.last().unwrap();
+            let next = endpoints.get(&key(tail))
+                .and_then(|touching| touching.iter().cloned().find(|&(id, _)| !used[id]));
+            match next {
+                Some((id, end)) => {
+                    used[id] = true;
+                    let other = if end == 0 { segments[id].1 } else { segments[id].0 };
+                    if key(other) == key(polygon[0]) {
+                        break;
+                    }
+                    polygon.push(other);
+                }
+                None => break
+            }
+        }
+        polygons.push(polygon);
+    }
+    polygons
+}
+
+/// Renders a closed polygon of grid-point coordinates as an SVG path,
+/// scaling back from grid cells to canvas coordinates.
+fn polygon_to_path(polygon : &Vec<(f64,f64)>, grid : &Grid) -> String {
+    let mut d = String::new();
+    for (i, &(gx, gy)) in polygon.iter().enumerate() {
+        let x = grid.min_x + gx * grid.cell;
+        let y = grid.min_y + gy * grid.cell;
+        if i == 0 {
+            d.push_str(&format!("M {:.2} {:.2} ", x, y));
+        } else {
+            d.push_str(&format!("L {:.2} {:.2} ", x, y));
+        }
+    }
+    d.push('Z');
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `Model` with every force except the one under test zeroed out,
+    /// and `canvas_rigidity` non-negative so the centre-attraction term
+    /// stays finite for vertices sitting at the origin.
+    fn bare_model() -> Model {
+        Model {
+            spring : 0.0,
+            repulse : 0.0,
+            repulse_dist : 0.0,
+            repulse_rigidity : 0.0,
+            canvas : 0.0,
+            canvas_size : 1.0,
+            canvas_rigidity : 2.0,
+            n_blocks : 4,
+            neighborhood : Neighborhood::Grid,
+            crossing : 0.0,
+            margin : 0.0,
+            manhattan : false
+        }
+    }
+
+    #[test]
+    fn crossing_gradient_uncrosses_a_known_pair() {
+        let mut g = Graph::new();
+        let a = g.add_vertex("a");
+        let b = g.add_vertex("b");
+        let c = g.add_vertex("c");
+        let d = g.add_vertex("d");
+        g.edges.push(Edge::new(a, b));
+        g.edges.push(Edge::new(c, d));
+
+        // Slightly off a perfect square so the fully symmetric starting
+        // point (which has zero net gradient once the pair shrinks to a
+        // point) doesn't mask whether the crossing actually resolves.
+        let mut loc = vec![0.0; 8];
+        loc[a * 2] = 0.0;  loc[a * 2 + 1] = 0.0;
+        loc[b * 2] = 10.0; loc[b * 2 + 1] = 10.0;
+        loc[c * 2] = 0.0;  loc[c * 2 + 1] = 10.01;
+        loc[d * 2] = 10.01; loc[d * 2 + 1] = 0.0;
+
+        let m = Model { crossing : 1.0, ..bare_model() };
+
+        for _ in 0..200 {
+            let grad = g.gradient(&loc, &m);
+            for i in 0..loc.len() {
+                loc[i] -= 0.05 * grad[i];
+            }
+        }
+
+        let p1 = (loc[a * 2], loc[a * 2 + 1]);
+        let p2 = (loc[b * 2], loc[b * 2 + 1]);
+        let p3 = (loc[c * 2], loc[c * 2 + 1]);
+        let p4 = (loc[d * 2], loc[d * 2 + 1]);
+        assert!(!segments_cross(p1, p2, p3, p4),
+                "edges still cross after descent: {:?} {:?} {:?} {:?}", p1, p2, p3, p4);
+    }
+
+    #[test]
+    fn crossing_gradient_uncrosses_a_pair_with_both_edge_directions() {
+        // build_graph stores every link as both Edge::new(v1,v2) and
+        // Edge::new(v2,v1); without deduping in `crossings`, the four
+        // directed combinations of a single real crossing push with
+        // opposite signs and cancel to a net-zero force.
+        let mut g = Graph::new();
+        let a = g.add_vertex("a");
+        let b = g.add_vertex("b");
+        let c = g.add_vertex("c");
+        let d = g.add_vertex("d");
+        g.edges.push(Edge::new(a, b));
+        g.edges.push(Edge::new(b, a));
+        g.edges.push(Edge::new(c, d));
+        g.edges.push(Edge::new(d, c));
+
+        let mut loc = vec![0.0; 8];
+        loc[a * 2] = 0.0;  loc[a * 2 + 1] = 0.0;
+        loc[b * 2] = 10.0; loc[b * 2 + 1] = 10.0;
+        loc[c * 2] = 0.0;  loc[c * 2 + 1] = 10.01;
+        loc[d * 2] = 10.01; loc[d * 2 + 1] = 0.0;
+
+        let m = Model { crossing : 1.0, ..bare_model() };
+
+        for _ in 0..200 {
+            let grad = g.gradient(&loc, &m);
+            for i in 0..loc.len() {
+                loc[i] -= 0.05 * grad[i];
+            }
+        }
+
+        let p1 = (loc[a * 2], loc[a * 2 + 1]);
+        let p2 = (loc[b * 2], loc[b * 2 + 1]);
+        let p3 = (loc[c * 2], loc[c * 2 + 1]);
+        let p4 = (loc[d * 2], loc[d * 2 + 1]);
+        assert!(!segments_cross(p1, p2, p3, p4),
+                "edges still cross after descent: {:?} {:?} {:?} {:?}", p1, p2, p3, p4);
+    }
+
+    #[test]
+    fn duplicate_points_still_get_neighbors() {
+        // Four coincident points among three well-separated ones.
+        let loc = vec![
+            0.0, 0.0,
+            0.0, 0.0,
+            0.0, 0.0,
+            0.0, 0.0,
+            5.0, 5.0,
+            -5.0, 5.0,
+            5.0, -5.0
+        ];
+        let n = loc.len() / 2;
+        let triangulation = Triangulation::build(&loc, n).expect("should still triangulate");
+        for v in 0..4 {
+            assert!(!triangulation.adjacency[v].is_empty(),
+                    "duplicate vertex {} was left with no neighbors", v);
+        }
+    }
+
+    #[test]
+    fn manhattan_coincident_pair_separates() {
+        let mut g = Graph::new();
+        g.add_vertex("a");
+        g.add_vertex("b");
+        let mut loc = vec![0.0; 4];
+
+        // n_blocks: 0 forces all-pairs neighbor testing (see
+        // Graph::neighbor_lists) so the pair keeps seeing each other once
+        // separated, rather than falling out of each other's Blocking cell.
+        let m = Model { repulse : 1.0, repulse_dist : 1.0, manhattan : true, n_blocks : 0, ..bare_model() };
+
+        for _ in 0..200 {
+            let grad = g.gradient(&loc, &m);
+            for i in 0..loc.len() {
+                loc[i] -= 0.1 * grad[i];
+            }
+        }
+
+        let dx = loc[0] - loc[2];
+        let dy = loc[1] - loc[3];
+        let d = dx.abs().max(dy.abs());
+        assert!(d > 0.5, "coincident manhattan pair never separated (d = {})", d);
+    }
+
+    #[test]
+    fn march_contours_a_single_peak_into_a_diamond() {
+        // A lone spike at grid point (1,1), flat zero everywhere else:
+        // each of the 4 cells touching it crosses the threshold at exactly
+        // one corner, and the four resulting segments should chain into
+        // one closed diamond around that point.
+        let field = vec![
+            vec![0.0, 0.0, 0.0],
+            vec![0.0, 1.0, 0.0],
+            vec![0.0, 0.0, 0.0],
+        ];
+        let grid = Grid { min_x : 0.0, min_y : 0.0, cell : 1.0, width : 2, height : 2 };
+
+        let polygons = march(&field, &grid, 0.5);
+        assert_eq!(polygons.len(), 1, "expected a single closed contour, got {:?}", polygons);
+
+        let polygon = &polygons[0];
+        let expected = [(1.0, 0.5), (0.5, 1.0), (1.0, 1.5), (1.5, 1.0)];
+        assert_eq!(polygon.len(), expected.len(),
+                   "expected a 4-point diamond, got {:?}", polygon);
+        for (p, e) in polygon.iter().zip(expected.iter()) {
+            assert!((p.0 - e.0).abs() < 1e-9 && (p.1 - e.1).abs() < 1e-9,
+                    "{:?} != {:?} in {:?}", p, e, polygon);
+        }
+    }
+
+    #[test]
+    fn density_field_peaks_at_a_bubble_and_fades_with_distance() {
+        let mut g = Graph::new();
+        g.add_vertex("a");
+        let loc = vec![1.0, 1.0];
+        let grid = Grid { min_x : 0.0, min_y : 0.0, cell : 1.0, width : 2, height : 2 };
+
+        let field = density_field(&g, &loc, &vec![0], &grid, 2.0);
+
+        assert!((field[1][1] - 1.0).abs() < 1e-9, "on-centre value should be 1.0: {:?}", field);
+        let expected_adjacent = 1.0 - 1.0 / 2.0;
+        assert!((field[1][0] - expected_adjacent).abs() < 1e-9, "{:?}", field);
+        assert!((field[0][1] - expected_adjacent).abs() < 1e-9, "{:?}", field);
+        assert!(field[0][0] < field[1][0], "density should fall off with distance: {:?}", field);
+    }
+
+    #[test]
+    fn domain_hulls_escapes_domain_and_color_in_svg_output() {
+        let mut g = Graph::new();
+        let a = g.add_vertex("a");
+        let b = g.add_vertex("b");
+        g.domains = vec!["news\"><script>".to_string(); 2];
+        let loc = vec![0.0, 0.0, 0.5, 0.0];
+        let _ = (a, b);
+
+        let mut colors = HashMap::new();
+        colors.insert("news\"><script>".to_string(), "red\"onload=\"x()".to_string());
+
+        let params = HullParams { width : 4, height : 4, influence : 2.0, threshold : 0.25 };
+        let svg = domain_hulls(&g, &loc, 2.0, &params, &colors);
+
+        assert!(!svg.is_empty(), "expected at least one hull to be drawn");
+        assert!(!svg.contains("<script>"), "raw markup leaked into SVG output: {}", svg);
+        assert!(!svg.contains("onload=\"x()\""), "unescaped color broke out of its attribute: {}", svg);
+        assert!(svg.contains("&quot;"), "expected the injected quote to be escaped: {}", svg);
+    }
+
+    #[test]
+    fn gradient_leaves_a_pinned_vertex_where_it_was_seeded() {
+        let mut g = Graph::new();
+        let a = g.add_vertex("a");
+        let b = g.add_vertex("b");
+        g.edges.push(Edge::new(a, b));
+        g.pinned = vec![true, false];
+        g.pin_loc = vec![3.0, 4.0, 0.0, 0.0];
+
+        let mut loc = g.seed_loc();
+        assert_eq!((loc[a * 2], loc[a * 2 + 1]), (3.0, 4.0));
+        assert_eq!((loc[b * 2], loc[b * 2 + 1]), (0.0, 0.0));
+
+        let m = Model { spring : 1.0, ..bare_model() };
+        for _ in 0..50 {
+            let grad = g.gradient(&loc, &m);
+            for i in 0..loc.len() {
+                loc[i] -= 0.05 * grad[i];
+            }
+        }
+
+        assert_eq!((loc[a * 2], loc[a * 2 + 1]), (3.0, 4.0),
+                   "pinned vertex moved during descent");
+        assert!(loc[b * 2] != 0.0 || loc[b * 2 + 1] != 0.0,
+                "unpinned vertex should have been pulled toward its pinned neighbor");
+
+        g.unpin_all();
+        let grad = g.gradient(&loc, &m);
+        assert!(grad[a * 2] != 0.0 || grad[a * 2 + 1] != 0.0,
+                "vertex should feel spring force again once unpinned");
+    }
+}